@@ -1,17 +1,25 @@
 use bitflags::bitflags;
 use core::slice::{from_raw_parts, from_raw_parts_mut};
 use lmdb::{
-    self, Cursor, Database, DatabaseFlags, Environment, InactiveTransaction, RwTransaction,
-    Transaction, WriteFlags,
+    self, Cursor, Database, DatabaseFlags, Environment, EnvironmentFlags, InactiveTransaction,
+    RwTransaction, Transaction, WriteFlags,
 };
+// lmdb::ffi is a private re-export of lmdb-sys, so cursor ops below need
+// lmdb-sys as a direct dependency
+use lmdb_sys as ffi;
 use std::collections::HashMap;
 use std::error::Error;
 use std::ffi::CStr;
 use std::io::{ErrorKind, Write};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_int};
 use std::path::Path;
 use std::ptr;
 
+// named databases must be declared up front via set_max_dbs, otherwise
+// creating/opening anything other than the default db fails with
+// MDB_DBS_FULL
+const DEFAULT_MAX_DBS: u32 = 128;
+
 #[repr(C)]
 pub enum ReturnCode {
     OK,
@@ -24,18 +32,96 @@ bitflags! {
     pub struct LMDBResultFlags: u8 {
         const NOT_FOUND = 0b00000001;
         const AGAIN = 0b00000010;
+        const CAS_FAILED = 0b00000100;
     }
 }
 
+bitflags! {
+    // only consulted when a db_name is first resolved by an op that may
+    // create it (Set, SetIfEquals or CreateDB in a write batch); ignored once
+    // the db is cached, including when another op opened it first
+    #[repr(C)]
+    pub struct LMDBDatabaseFlags: u8 {
+        const INTEGER_KEY = 0b00000001;
+        const DUP_SORT = 0b00000010;
+    }
+}
+
+bitflags! {
+    // mirrors a subset of lmdb::EnvironmentFlags that is safe to expose
+    // over FFI; see ngx_lmdb_handle_open
+    #[repr(C)]
+    pub struct LMDBEnvironmentFlags: u32 {
+        const NO_SUB_DIR = 0b00000001;
+        const MAP_ASYNC = 0b00000010;
+        const WRITE_MAP = 0b00000100;
+        const NO_SYNC = 0b00001000;
+    }
+}
+
+// the key ordering a database is created with, consulted once at
+// db-creation time to decide whether to register the integer comparator
+#[derive(Clone, Copy, PartialEq)]
+enum KeyKind {
+    Bytes,
+    Integer,
+}
+
+impl From<LMDBDatabaseFlags> for KeyKind {
+    fn from(flags: LMDBDatabaseFlags) -> Self {
+        if flags.contains(LMDBDatabaseFlags::INTEGER_KEY) {
+            KeyKind::Integer
+        } else {
+            KeyKind::Bytes
+        }
+    }
+}
+
+struct DbHandle {
+    dbi: Database,
+}
+
 #[repr(C)]
 pub struct LMDBOperationArgs {
+    db_name: *const u8,
+    db_name_len: usize,
+    db_flags: LMDBDatabaseFlags,
     key: *const u8,
     key_len: usize,
     value: *mut u8,
     value_len: usize,
+    // GetAll only: per-value length output array and its capacity, plus the
+    // number of values actually written back through value_count
+    value_lens: *mut i32,
+    value_lens_cap: usize,
+    value_count: *mut usize,
+    // SetIfEquals only: the value the caller expects to find at key; a null
+    // pointer means "expect the key to be absent"
+    expected_value: *const u8,
+    expected_value_len: usize,
     flags: LMDBResultFlags,
 }
 
+extern "C" fn integer_key_cmp(a: *const ffi::MDB_val, b: *const ffi::MDB_val) -> c_int {
+    // keys point into LMDB's mmap'd pages and are not guaranteed to be
+    // 8-byte aligned, so this must be an unaligned read; also guard against
+    // a key of the wrong size instead of reading past the value
+    unsafe fn read_u64(v: *const ffi::MDB_val) -> Option<u64> {
+        if (*v).mv_size != std::mem::size_of::<u64>() {
+            return None;
+        }
+        Some(ptr::read_unaligned((*v).mv_data as *const u64))
+    }
+
+    unsafe {
+        match (read_u64(a), read_u64(b)) {
+            (Some(a), Some(b)) => a.cmp(&b) as c_int,
+            // malformed key: no well-defined ordering, treat as equal
+            _ => 0,
+        }
+    }
+}
+
 #[repr(C)]
 pub enum LMDBOperationCode {
     Get,
@@ -43,6 +129,9 @@ pub enum LMDBOperationCode {
     CreateDB,
     DropDB,
     ClearDB,
+    DelValue,
+    GetAll,
+    SetIfEquals,
 }
 
 #[repr(C)]
@@ -68,7 +157,7 @@ pub struct LMDBHandle<'env> {
     inactive_txn: Option<InactiveTransaction<'env>>,
     last_err: Option<lmdb::Error>,
     default_db: Database,
-    databases: HashMap<String, Database>,
+    databases: HashMap<String, DbHandle>,
 }
 
 #[no_mangle]
@@ -87,11 +176,44 @@ pub extern "C" fn ngx_lmdb_handle_get_last_err(handle: &mut LMDBHandle) -> *cons
 pub extern "C" fn ngx_lmdb_handle_open<'env>(
     path: *const c_char,
     perm: u32,
+    map_size: usize,
+    max_dbs: u32,
+    max_readers: u32,
+    env_flags: LMDBEnvironmentFlags,
     err: *mut *const u8,
 ) -> *mut LMDBHandle<'env> {
     let path = unsafe { CStr::from_ptr(path).to_str().unwrap() };
 
-    match Environment::new().open_with_permissions(Path::new(path), perm) {
+    let mut builder = Environment::new();
+    builder.set_max_dbs(if max_dbs > 0 {
+        max_dbs
+    } else {
+        DEFAULT_MAX_DBS
+    });
+
+    if map_size > 0 {
+        builder.set_map_size(map_size);
+    }
+    if max_readers > 0 {
+        builder.set_max_readers(max_readers);
+    }
+
+    let mut flags = EnvironmentFlags::empty();
+    if env_flags.contains(LMDBEnvironmentFlags::NO_SUB_DIR) {
+        flags.insert(EnvironmentFlags::NO_SUB_DIR);
+    }
+    if env_flags.contains(LMDBEnvironmentFlags::MAP_ASYNC) {
+        flags.insert(EnvironmentFlags::MAP_ASYNC);
+    }
+    if env_flags.contains(LMDBEnvironmentFlags::WRITE_MAP) {
+        flags.insert(EnvironmentFlags::WRITE_MAP);
+    }
+    if env_flags.contains(LMDBEnvironmentFlags::NO_SYNC) {
+        flags.insert(EnvironmentFlags::NO_SYNC);
+    }
+    builder.set_flags(flags);
+
+    match builder.open_with_permissions(Path::new(path), perm) {
         Ok(env) => {
             let default_db = env.open_db(None).unwrap();
             Box::into_raw(Box::new(LMDBHandle {
@@ -168,10 +290,137 @@ fn execute_lmdb_set(
     }
 }
 
+fn execute_lmdb_set_if_equals(
+    dbi: Database,
+    txn: &mut RwTransaction,
+    opt: &mut LMDBOperationArgs,
+) -> lmdb::Result<()> {
+    let key = unsafe { from_raw_parts(opt.key, opt.key_len) };
+    let expected = if opt.expected_value.is_null() {
+        None
+    } else {
+        Some(unsafe { from_raw_parts(opt.expected_value, opt.expected_value_len) })
+    };
+
+    let current = match txn.get(dbi, &key) {
+        Ok(val) => Some(val),
+        Err(lmdb::Error::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+
+    if current != expected {
+        opt.flags.insert(LMDBResultFlags::CAS_FAILED);
+        return Ok(());
+    }
+
+    execute_lmdb_set(dbi, txn, opt)
+}
+
 fn execute_lmdb_cleardb(dbi: Database, txn: &mut RwTransaction) -> lmdb::Result<()> {
     txn.clear_db(dbi)
 }
 
+fn execute_lmdb_delvalue(
+    dbi: Database,
+    txn: &mut RwTransaction,
+    opt: &mut LMDBOperationArgs,
+) -> lmdb::Result<()> {
+    let key = unsafe { from_raw_parts(opt.key, opt.key_len) };
+    let value = unsafe { from_raw_parts(opt.value, opt.value_len) };
+
+    match txn.del(dbi, &key, Some(value)) {
+        Ok(_) | Err(lmdb::Error::NotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn execute_lmdb_getall<T: Transaction>(
+    dbi: Database,
+    txn: &T,
+    opt: &mut LMDBOperationArgs,
+) -> lmdb::Result<()> {
+    let key = unsafe { from_raw_parts(opt.key, opt.key_len) };
+    let mut value = unsafe { from_raw_parts_mut(opt.value, opt.value_len) };
+    let value_lens = unsafe { from_raw_parts_mut(opt.value_lens, opt.value_lens_cap) };
+
+    let cursor = txn.open_ro_cursor(dbi)?;
+
+    let mut cur = match cursor.get(Some(key), None, ffi::MDB_SET) {
+        Ok(kv) => Some(kv),
+        Err(lmdb::Error::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+
+    let mut n = 0;
+    while let Some((_, val)) = cur {
+        if n == value_lens.len() {
+            opt.flags.insert(LMDBResultFlags::AGAIN);
+            break;
+        }
+
+        value_lens[n] = val.len() as i32;
+        if let Err(e) = value.write_all(val) {
+            if e.kind() == ErrorKind::WriteZero {
+                opt.flags.insert(LMDBResultFlags::AGAIN);
+                break;
+            }
+            // memory writes should never fail for other reasons
+            unreachable!();
+        }
+
+        n += 1;
+        cur = match cursor.get(None, None, ffi::MDB_NEXT_DUP) {
+            Ok(kv) => Some(kv),
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+    }
+
+    if n == 0 {
+        opt.flags.insert(LMDBResultFlags::NOT_FOUND);
+    }
+
+    unsafe {
+        *opt.value_count = n;
+    }
+
+    Ok(())
+}
+
+fn execute_lmdb_dropdb(dbi: Database, txn: &mut RwTransaction) -> lmdb::Result<()> {
+    // the caller evicts the dbi cached in handle.databases as soon as this
+    // returns, and no cursor is held open on it within this batch; a later op
+    // in the same batch that names this db still carries the dbi resolved
+    // before the transaction began, which LMDB rejects as an invalid handle,
+    // failing the whole batch
+    unsafe { txn.drop_db(dbi) }
+}
+
+fn db_name(opt: &LMDBOperationArgs) -> lmdb::Result<Option<&str>> {
+    if opt.db_name.is_null() || opt.db_name_len == 0 {
+        return Ok(None);
+    }
+
+    let name = unsafe { from_raw_parts(opt.db_name, opt.db_name_len) };
+    match std::str::from_utf8(name) {
+        Ok(name) => Ok(Some(name)),
+        // db names come straight from the Lua caller's byte string; reject
+        // malformed input instead of panicking across the FFI boundary
+        Err(_) => Err(lmdb::Error::BadValSize),
+    }
+}
+
+fn resolve_dbi(
+    handle: &mut LMDBHandle,
+    opt: &LMDBOperationArgs,
+    create: bool,
+) -> lmdb::Result<Database> {
+    match db_name(opt)? {
+        Some(name) => get_database_handle(handle, name, create, opt.db_flags),
+        None => Ok(handle.default_db),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn ngx_lmdb_handle_execute(
     handle: &mut LMDBHandle,
@@ -180,31 +429,59 @@ pub extern "C" fn ngx_lmdb_handle_execute(
     write: bool,
 ) -> ReturnCode {
     let ops = unsafe { from_raw_parts_mut(ops, n) };
+
+    // get_database_handle opens (and commits) its own top-level transaction
+    // the first time a given db_name is seen, so every dbi this batch needs
+    // must be resolved up front, before the shared transaction below is
+    // opened -- LMDB only allows a single open transaction per thread, and
+    // nesting one inside the other deadlocks the writer lock.
+    let mut dbis = Vec::with_capacity(ops.len());
+    for op in ops.iter() {
+        let create = write
+            && matches!(
+                op.op_code,
+                LMDBOperationCode::Set
+                    | LMDBOperationCode::CreateDB
+                    | LMDBOperationCode::SetIfEquals
+            );
+        dbis.push(try_lmdb!(handle, resolve_dbi(handle, &op.args, create)));
+    }
+
     if write {
         let mut txn = try_lmdb!(handle, handle.env.begin_rw_txn());
 
-        for op in ops {
+        for (op, &dbi) in ops.iter_mut().zip(dbis.iter()) {
             op.args.flags = LMDBResultFlags::empty();
 
             match op.op_code {
                 LMDBOperationCode::Get => {
-                    try_lmdb!(
-                        handle,
-                        execute_lmdb_get(handle.default_db, &txn, &mut op.args)
-                    );
+                    try_lmdb!(handle, execute_lmdb_get(dbi, &txn, &mut op.args));
                 }
                 LMDBOperationCode::Set => {
-                    try_lmdb!(
-                        handle,
-                        execute_lmdb_set(handle.default_db, &mut txn, &mut op.args)
-                    );
+                    try_lmdb!(handle, execute_lmdb_set(dbi, &mut txn, &mut op.args));
                     assert!(op.args.flags.is_empty());
                 }
+                LMDBOperationCode::CreateDB => (),
+                LMDBOperationCode::DropDB => {
+                    let name =
+                        try_lmdb!(handle, db_name(&op.args)).expect("DropDB requires a db_name");
+                    try_lmdb!(handle, execute_lmdb_dropdb(dbi, &mut txn));
+                    handle.databases.remove(name);
+                }
                 LMDBOperationCode::ClearDB => {
-                    try_lmdb!(handle, execute_lmdb_cleardb(handle.default_db, &mut txn));
+                    try_lmdb!(handle, execute_lmdb_cleardb(dbi, &mut txn));
                     assert!(op.args.flags.is_empty());
                 }
-                _ => (),
+                LMDBOperationCode::DelValue => {
+                    try_lmdb!(handle, execute_lmdb_delvalue(dbi, &mut txn, &mut op.args));
+                    assert!(op.args.flags.is_empty());
+                }
+                LMDBOperationCode::GetAll => {
+                    try_lmdb!(handle, execute_lmdb_getall(dbi, &txn, &mut op.args));
+                }
+                LMDBOperationCode::SetIfEquals => {
+                    try_lmdb!(handle, execute_lmdb_set_if_equals(dbi, &mut txn, &mut op.args));
+                }
             };
         }
         try_lmdb!(handle, txn.commit());
@@ -214,13 +491,13 @@ pub extern "C" fn ngx_lmdb_handle_execute(
             Some(t) => try_lmdb!(handle, t.renew()),
         };
 
-        for op in ops {
+        for (op, &dbi) in ops.iter_mut().zip(dbis.iter()) {
             match op.op_code {
                 LMDBOperationCode::Get => {
-                    try_lmdb!(
-                        handle,
-                        execute_lmdb_get(handle.default_db, &txn, &mut op.args)
-                    );
+                    try_lmdb!(handle, execute_lmdb_get(dbi, &txn, &mut op.args));
+                }
+                LMDBOperationCode::GetAll => {
+                    try_lmdb!(handle, execute_lmdb_getall(dbi, &txn, &mut op.args));
                 }
                 _ => (),
             };
@@ -247,7 +524,7 @@ pub extern "C" fn ngx_lmdb_handle_get_databases<'env>(
 
     let mut cursor = try_lmdb!(handle, txn.open_ro_cursor(handle.default_db));
     for (i, v) in cursor.iter().enumerate() {
-        let (key, _val) = try_lmdb!(handle, v);
+        let (key, _val) = v;
         value_lens[i] = key.len() as i32;
         if let Err(e) = values_buf.write_all(key) {
             if e.kind() == ErrorKind::WriteZero {
@@ -264,20 +541,259 @@ pub extern "C" fn ngx_lmdb_handle_get_databases<'env>(
     ReturnCode::OK
 }
 
+#[no_mangle]
+pub extern "C" fn ngx_lmdb_handle_get_range<'env>(
+    handle: &'env mut LMDBHandle<'env>,
+    db_name: *const u8,
+    db_name_len: usize,
+    start_key: *const u8,
+    start_key_len: usize,
+    end_key: *const u8,
+    end_key_len: usize,
+    reverse: bool,
+    limit: usize,
+    keys_buf: *mut u8,
+    keys_buf_len: usize,
+    key_lens: *mut i32,
+    values_buf: *mut u8,
+    values_buf_len: usize,
+    value_lens: *mut i32,
+    out_count: *mut usize,
+) -> ReturnCode {
+    let args = LMDBOperationArgs {
+        db_name,
+        db_name_len,
+        db_flags: LMDBDatabaseFlags::empty(),
+        key: ptr::null(),
+        key_len: 0,
+        value: ptr::null_mut(),
+        value_len: 0,
+        value_lens: ptr::null_mut(),
+        value_lens_cap: 0,
+        value_count: ptr::null_mut(),
+        expected_value: ptr::null(),
+        expected_value_len: 0,
+        flags: LMDBResultFlags::empty(),
+    };
+    let dbi = try_lmdb!(handle, resolve_dbi(handle, &args, false));
+
+    let start_key = if start_key.is_null() {
+        None
+    } else {
+        Some(unsafe { from_raw_parts(start_key, start_key_len) })
+    };
+    let end_key = if end_key.is_null() {
+        None
+    } else {
+        Some(unsafe { from_raw_parts(end_key, end_key_len) })
+    };
+
+    let key_lens = unsafe { from_raw_parts_mut(key_lens, limit) };
+    let value_lens = unsafe { from_raw_parts_mut(value_lens, limit) };
+    let mut keys_buf = unsafe { from_raw_parts_mut(keys_buf, keys_buf_len) };
+    let mut values_buf = unsafe { from_raw_parts_mut(values_buf, values_buf_len) };
+
+    let txn = match handle.inactive_txn.take() {
+        None => try_lmdb!(handle, handle.env.begin_ro_txn()),
+        Some(t) => try_lmdb!(handle, t.renew()),
+    };
+
+    let cursor = try_lmdb!(handle, txn.open_ro_cursor(dbi));
+
+    let next_op = if reverse { ffi::MDB_PREV } else { ffi::MDB_NEXT };
+
+    let mut cur = match start_key {
+        Some(start) => match cursor.get(Some(start), None, ffi::MDB_SET_RANGE) {
+            Ok((Some(k), v)) => {
+                if reverse && k != start {
+                    // MDB_SET_RANGE lands on the first key >= start; a reverse
+                    // scan wants the first key <= start, so step back once
+                    // unless we landed exactly on it
+                    cursor.get(None, None, ffi::MDB_PREV).ok()
+                } else {
+                    Some((Some(k), v))
+                }
+            }
+            Ok(_) => None,
+            Err(lmdb::Error::NotFound) if reverse => cursor.get(None, None, ffi::MDB_LAST).ok(),
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => {
+                handle.last_err = Some(e);
+                return ReturnCode::ERR;
+            }
+        },
+        None if reverse => cursor.get(None, None, ffi::MDB_LAST).ok(),
+        None => cursor.get(None, None, ffi::MDB_FIRST).ok(),
+    };
+
+    let mut n = 0;
+    let mut truncated = false;
+    while let Some((Some(key), val)) = cur {
+        if let Some(end) = end_key {
+            // end_key is exclusive in both directions
+            let past_end = if reverse { key <= end } else { key >= end };
+            if past_end {
+                break;
+            }
+        }
+
+        if n == limit {
+            break;
+        }
+
+        key_lens[n] = key.len() as i32;
+        value_lens[n] = val.len() as i32;
+
+        if let Err(e) = keys_buf.write_all(key) {
+            if e.kind() == ErrorKind::WriteZero {
+                truncated = true;
+                break;
+            }
+            unreachable!();
+        }
+        if let Err(e) = values_buf.write_all(val) {
+            if e.kind() == ErrorKind::WriteZero {
+                truncated = true;
+                break;
+            }
+            unreachable!();
+        }
+
+        n += 1;
+        cur = cursor.get(None, None, next_op).ok();
+    }
+
+    drop(cursor);
+
+    unsafe {
+        *out_count = n;
+    }
+
+    handle.inactive_txn = Some(txn.reset());
+
+    if truncated {
+        ReturnCode::AGAIN
+    } else {
+        ReturnCode::OK
+    }
+}
+
+#[repr(C)]
+pub struct LMDBStat {
+    page_size: u32,
+    depth: u32,
+    branch_pages: usize,
+    leaf_pages: usize,
+    overflow_pages: usize,
+    entries: usize,
+    map_size: usize,
+    last_pgno: usize,
+}
+
+// the lmdb crate only exposes env-wide (default db) mdb_env_stat and has no
+// binding at all for mdb_env_info, so per-database stats and the env's
+// map_size/last_pgno have to go through lmdb-sys directly
+fn check_mdb(rc: c_int) -> lmdb::Result<()> {
+    if rc == ffi::MDB_SUCCESS {
+        Ok(())
+    } else {
+        Err(lmdb::Error::from_err_code(rc))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ngx_lmdb_handle_stat<'env>(
+    handle: &'env mut LMDBHandle<'env>,
+    db_name: *const u8,
+    db_name_len: usize,
+    stat: *mut LMDBStat,
+) -> ReturnCode {
+    let args = LMDBOperationArgs {
+        db_name,
+        db_name_len,
+        db_flags: LMDBDatabaseFlags::empty(),
+        key: ptr::null(),
+        key_len: 0,
+        value: ptr::null_mut(),
+        value_len: 0,
+        value_lens: ptr::null_mut(),
+        value_lens_cap: 0,
+        value_count: ptr::null_mut(),
+        expected_value: ptr::null(),
+        expected_value_len: 0,
+        flags: LMDBResultFlags::empty(),
+    };
+    let dbi = try_lmdb!(handle, resolve_dbi(handle, &args, false));
+
+    let txn = match handle.inactive_txn.take() {
+        None => try_lmdb!(handle, handle.env.begin_ro_txn()),
+        Some(t) => try_lmdb!(handle, t.renew()),
+    };
+
+    let mut db_stat: ffi::MDB_stat = unsafe { std::mem::zeroed() };
+    let rc = unsafe { ffi::mdb_stat(txn.txn(), dbi.dbi(), &mut db_stat) };
+    try_lmdb!(handle, check_mdb(rc));
+
+    let mut env_info: ffi::MDB_envinfo = unsafe { std::mem::zeroed() };
+    let rc = unsafe { ffi::mdb_env_info(handle.env.env(), &mut env_info) };
+    try_lmdb!(handle, check_mdb(rc));
+
+    unsafe {
+        *stat = LMDBStat {
+            page_size: db_stat.ms_psize as u32,
+            depth: db_stat.ms_depth as u32,
+            branch_pages: db_stat.ms_branch_pages as usize,
+            leaf_pages: db_stat.ms_leaf_pages as usize,
+            overflow_pages: db_stat.ms_overflow_pages as usize,
+            entries: db_stat.ms_entries as usize,
+            map_size: env_info.me_mapsize as usize,
+            last_pgno: env_info.me_last_pgno as usize,
+        };
+    }
+
+    handle.inactive_txn = Some(txn.reset());
+    ReturnCode::OK
+}
+
 fn get_database_handle(
     handle: &mut LMDBHandle,
     name: &str,
     create: bool,
+    db_flags: LMDBDatabaseFlags,
 ) -> lmdb::Result<Database> {
     match handle.databases.get(name) {
-        Some(dbi) => Ok(*dbi),
+        Some(db) => Ok(db.dbi),
         None => {
+            let key_kind = KeyKind::from(db_flags);
+            let dup_sort = db_flags.contains(LMDBDatabaseFlags::DUP_SORT);
+
             let dbi = if create {
-                handle.env.create_db(Some(name), DatabaseFlags::empty())?
+                let mut flags = DatabaseFlags::empty();
+                if key_kind == KeyKind::Integer {
+                    flags.insert(DatabaseFlags::INTEGER_KEY);
+                }
+                if dup_sort {
+                    flags.insert(DatabaseFlags::DUP_SORT);
+                }
+
+                let dbi = handle.env.create_db(Some(name), flags)?;
+
+                if key_kind == KeyKind::Integer {
+                    let txn = handle.env.begin_rw_txn()?;
+                    // mdb_set_compare takes a pointer to the comparator fn
+                    // pointer, not the fn pointer itself
+                    let mut cmp: ffi::MDB_cmp_func = integer_key_cmp;
+                    unsafe {
+                        ffi::mdb_set_compare(txn.txn(), dbi.dbi(), &mut cmp);
+                    }
+                    txn.commit()?;
+                }
+
+                dbi
             } else {
                 handle.env.open_db(Some(name))?
             };
-            handle.databases.insert(name.to_string(), dbi);
+            handle.databases.insert(name.to_string(), DbHandle { dbi });
             Ok(dbi)
         }
     }